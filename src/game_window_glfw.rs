@@ -2,6 +2,7 @@
 
 // External crates.
 use gfx;
+use libc::{c_ulong, c_void};
 use collections::Deque;
 use collections::ringbuf::RingBuf;
 use glfw;
@@ -10,10 +11,149 @@ use gl;
 use piston::input;
 use piston::input::keyboard;
 use piston::input::mouse;
+use piston::input::{ControllerAxisArgs, ControllerButton};
 use piston::GameWindow;
 use piston::GameWindowSettings;
 use piston::shader_version::opengl::OpenGL;
 
+/// Number of joystick slots GLFW exposes (Joystick1..Joystick16).
+const MAX_JOYSTICKS: uint = 16;
+
+/// How far an axis has to move away from its last reported value
+/// before it is reported again, to suppress analog stick jitter.
+const JOYSTICK_DEADZONE: f64 = 0.15;
+
+static JOYSTICK_IDS: [glfw::JoystickId, ..MAX_JOYSTICKS] = [
+    glfw::Joystick1, glfw::Joystick2, glfw::Joystick3, glfw::Joystick4,
+    glfw::Joystick5, glfw::Joystick6, glfw::Joystick7, glfw::Joystick8,
+    glfw::Joystick9, glfw::Joystick10, glfw::Joystick11, glfw::Joystick12,
+    glfw::Joystick13, glfw::Joystick14, glfw::Joystick15, glfw::Joystick16,
+];
+
+/// A gamepad button, normalized to a standard layout (face buttons,
+/// bumpers, thumbsticks and dpad) so the mapping is consistent
+/// across different controllers.
+#[deriving(Clone, PartialEq, Show)]
+pub enum GamepadButton {
+    GamepadA,
+    GamepadB,
+    GamepadX,
+    GamepadY,
+    GamepadLeftBumper,
+    GamepadRightBumper,
+    GamepadBack,
+    GamepadStart,
+    GamepadGuide,
+    GamepadLeftThumb,
+    GamepadRightThumb,
+    GamepadDpadUp,
+    GamepadDpadRight,
+    GamepadDpadDown,
+    GamepadDpadLeft,
+    // Carries the raw index so overflow buttons (e.g. a touchpad
+    // click or mic-mute button beyond the standard layout) stay
+    // distinguishable from one another instead of colliding.
+    GamepadUnknown(u8),
+}
+
+impl GamepadButton {
+    /// The wire code sent to the game as `ControllerButton.button`.
+    /// Matches the index `glfw_map_joystick_button` assigned it.
+    fn code(&self) -> u8 {
+        match *self {
+            GamepadA => 0,
+            GamepadB => 1,
+            GamepadX => 2,
+            GamepadY => 3,
+            GamepadLeftBumper => 4,
+            GamepadRightBumper => 5,
+            GamepadBack => 6,
+            GamepadStart => 7,
+            GamepadGuide => 8,
+            GamepadLeftThumb => 9,
+            GamepadRightThumb => 10,
+            GamepadDpadUp => 11,
+            GamepadDpadRight => 12,
+            GamepadDpadDown => 13,
+            GamepadDpadLeft => 14,
+            GamepadUnknown(index) => index,
+        }
+    }
+}
+
+/// A gamepad analog axis, normalized to a standard layout
+/// (both thumbsticks and both triggers).
+#[deriving(Clone, PartialEq, Show)]
+pub enum GamepadAxis {
+    GamepadLeftStickX,
+    GamepadLeftStickY,
+    GamepadRightStickX,
+    GamepadRightStickY,
+    GamepadLeftTrigger,
+    GamepadRightTrigger,
+    // Carries the raw index so overflow axes stay distinguishable.
+    GamepadUnknownAxis(u8),
+}
+
+impl GamepadAxis {
+    /// The wire code sent to the game as `ControllerAxisArgs.axis`.
+    /// Matches the index `glfw_map_joystick_axis` assigned it.
+    fn code(&self) -> u8 {
+        match *self {
+            GamepadLeftStickX => 0,
+            GamepadLeftStickY => 1,
+            GamepadRightStickX => 2,
+            GamepadRightStickY => 3,
+            GamepadLeftTrigger => 4,
+            GamepadRightTrigger => 5,
+            GamepadUnknownAxis(index) => index,
+        }
+    }
+}
+
+/// Snapshot of a single joystick's buttons and axes, kept around so
+/// `flush_messages` can diff the next poll against it.
+struct JoystickState {
+    buttons: Vec<bool>,
+    // The axis value as last reported in a `Move` event. Compared
+    // against on every poll, rather than the raw previous-poll value,
+    // so a slow drift still crosses the deadzone eventually and a
+    // stick released in small steps still gets a last report within
+    // the deadzone of where it comes to rest.
+    reported_axes: Vec<f32>,
+}
+
+/// Number of `CursorShape` variants, and the size of the cache
+/// backing `GameWindowGLFW::set_cursor`.
+const CURSOR_SHAPE_COUNT: uint = 6;
+
+/// A standard mouse cursor shape, mapped to a GLFW standard cursor.
+#[deriving(Clone, PartialEq, Show)]
+pub enum CursorShape {
+    ArrowCursor,
+    IBeamCursor,
+    CrosshairCursor,
+    HandCursor,
+    HResizeCursor,
+    VResizeCursor,
+}
+
+/// The native platform handle backing a `GameWindowGLFW`, for interop
+/// with renderers that bypass GLFW's own GL context (Vulkan surfaces,
+/// a separate UI layer, etc).
+pub enum NativeHandle {
+    /// The Win32 `HWND`.
+    #[cfg(target_os = "windows")]
+    Win32(*mut c_void),
+    /// The X11 `Display` pointer and the `Window` XID. `Window` is
+    /// `unsigned long`, not a pointer, so it's kept as `c_ulong`.
+    #[cfg(target_os = "linux")]
+    X11(*mut c_void, c_ulong),
+    /// The Cocoa `NSWindow`.
+    #[cfg(target_os = "macos")]
+    Cocoa(*mut c_void),
+}
+
 /// Contains stuff for game window.
 pub struct GameWindowGLFW {
     /// The window.
@@ -27,10 +167,25 @@ pub struct GameWindowGLFW {
     event_queue: RingBuf<input::InputEvent>,
     // Used to compute relative mouse movement.
     last_mouse_pos: Option<(f64, f64)>,
+    // Last known state of each joystick slot, `None` when the slot
+    // is not currently present.
+    joysticks: Vec<Option<JoystickState>>,
+    // Modifier keys (Shift/Ctrl/Alt/Super) held as of the last key event
+    // passed on to the game (not an Escape press taken by exit_on_esc).
+    modifiers: keyboard::ModifierKey,
+    // Native cursors, created lazily and cached by `CursorShape`.
+    cursors: [Option<glfw::Cursor>, ..CURSOR_SHAPE_COUNT],
 }
 
 impl GameWindowGLFW {
     /// Create a new game window from an existing GLFW window.
+    ///
+    /// The window-creation hints (samples, vsync, resizable, decorated)
+    /// are already baked into `win` by the time it reaches us and can't
+    /// be read back, so the settings built here assume the common
+    /// defaults for them. If the caller wants something other than the
+    /// default swap interval, call `self.glfw.set_swap_interval` after
+    /// this returns (the window is already current at that point).
     pub fn from_pieces(win: glfw::Window, glfw: glfw::Glfw,
                        events: Receiver<(f64, glfw::WindowEvent)>,
                        exit_on_esc: bool) -> GameWindowGLFW {
@@ -38,6 +193,9 @@ impl GameWindowGLFW {
         win.set_mouse_button_polling(true);
         win.set_cursor_pos_polling(true);
         win.set_scroll_polling(true);
+        win.set_framebuffer_size_polling(true);
+        win.set_focus_polling(true);
+        win.set_close_polling(true);
         win.make_current();
 
         let (w, h) = win.get_framebuffer_size();
@@ -52,9 +210,16 @@ impl GameWindowGLFW {
                 size: [w as u32, h as u32],
                 fullscreen: fullscreen,
                 exit_on_esc: exit_on_esc,
+                samples: 0,
+                vsync: false,
+                resizable: true,
+                decorated: true,
             },
             event_queue: RingBuf::new(),
             last_mouse_pos: None,
+            joysticks: Vec::from_fn(MAX_JOYSTICKS, |_| None),
+            modifiers: keyboard::NO_MODIFIER,
+            cursors: [None, None, None, None, None, None],
         }
     }
 
@@ -71,6 +236,11 @@ impl GameWindowGLFW {
         glfw.window_hint(glfw::ContextVersion(major as u32, minor as u32));
         glfw.window_hint(glfw::OpenglForwardCompat(true));
         glfw.window_hint(glfw::OpenglProfile(glfw::OpenGlCoreProfile));
+        if settings.samples > 0 {
+            glfw.window_hint(glfw::Samples(settings.samples as u32));
+        }
+        glfw.window_hint(glfw::Resizable(settings.resizable));
+        glfw.window_hint(glfw::Decorated(settings.decorated));
 
         // Create GLFW window.
         let (window, events) = glfw.create_window(
@@ -83,6 +253,9 @@ impl GameWindowGLFW {
         window.set_cursor_pos_polling(true);
         window.set_scroll_polling(true);
         window.set_char_polling(true);
+        window.set_framebuffer_size_polling(true);
+        window.set_focus_polling(true);
+        window.set_close_polling(true);
         // or polling all event
         //window.set_all_polling(true);
         window.make_current();
@@ -90,6 +263,10 @@ impl GameWindowGLFW {
         // Load the OpenGL function pointers
         gl::load_with(|s| glfw.get_proc_address(s));
 
+        // Tear-free rendering is opt-in via the settings, not a hint,
+        // since it has to be set after the context is current.
+        glfw.set_swap_interval(if settings.vsync { 1 } else { 0 });
+
         GameWindowGLFW {
             window: window,
             events: events,
@@ -97,6 +274,63 @@ impl GameWindowGLFW {
             settings: settings,
             event_queue: RingBuf::new(),
             last_mouse_pos: None,
+            joysticks: Vec::from_fn(MAX_JOYSTICKS, |_| None),
+            modifiers: keyboard::NO_MODIFIER,
+            cursors: [None, None, None, None, None, None],
+        }
+    }
+
+    /// Polls the connected joysticks and pushes `Press`/`Release`/`Move`
+    /// events for whatever changed since the last poll.
+    fn poll_joysticks(&mut self) {
+        for (slot, &id) in JOYSTICK_IDS.iter().enumerate() {
+            let joystick = self.glfw.get_joystick(id);
+
+            if !joystick.is_present() {
+                self.joysticks[slot] = None;
+                continue;
+            }
+
+            let buttons: Vec<bool> = joystick.get_buttons().iter()
+                .map(|&b| b != 0).collect();
+            let axes = joystick.get_axes();
+
+            let reported_axes = match self.joysticks[slot] {
+                Some(ref prev) => {
+                    for (i, (&was, &is)) in prev.buttons.iter()
+                        .zip(buttons.iter()).enumerate() {
+                        if was == is { continue; }
+                        let button = glfw_map_joystick_button(i);
+                        let event = if is { input::Press } else { input::Release };
+                        self.event_queue.push(
+                            event(input::Controller(
+                                ControllerButton { id: slot as u32, button: button.code() }
+                            ))
+                        );
+                    }
+                    let mut reported = Vec::with_capacity(axes.len());
+                    for (i, (&was_reported, &is)) in prev.reported_axes.iter()
+                        .zip(axes.iter()).enumerate() {
+                        if (is - was_reported).abs() as f64 <= JOYSTICK_DEADZONE {
+                            reported.push(was_reported);
+                            continue;
+                        }
+                        let axis = glfw_map_joystick_axis(i);
+                        self.event_queue.push(
+                            input::Move(input::ControllerAxis(ControllerAxisArgs {
+                                id: slot as u32,
+                                axis: axis.code(),
+                                position: is as f64,
+                            }))
+                        );
+                        reported.push(is);
+                    }
+                    reported
+                }
+                None => axes.clone(),
+            };
+
+            self.joysticks[slot] = Some(JoystickState { buttons: buttons, reported_axes: reported_axes });
         }
     }
 
@@ -106,6 +340,7 @@ impl GameWindowGLFW {
         }
 
         self.glfw.poll_events();
+        self.poll_joysticks();
         for (_, event) in glfw::flush_messages(&self.events) {
             match event {
                 glfw::KeyEvent(glfw::KeyEscape, _, glfw::Press, _)
@@ -115,16 +350,24 @@ impl GameWindowGLFW {
                 glfw::CharEvent(ch) => {
                     self.event_queue.push(input::Text(ch.to_string()));
                 }
-                glfw::KeyEvent(key, _, glfw::Press, _) => {
+                glfw::KeyEvent(key, _, glfw::Press, modifiers) => {
+                    self.modifiers = glfw_map_modifiers(modifiers);
                     self.event_queue.push(
                         input::Press(input::Keyboard(glfw_map_key(key)))
                     );
                 }
-                glfw::KeyEvent(key, _, glfw::Release, _) => {
+                glfw::KeyEvent(key, _, glfw::Release, modifiers) => {
+                    self.modifiers = glfw_map_modifiers(modifiers);
                     self.event_queue.push(
                         input::Release(input::Keyboard(glfw_map_key(key)))
                     );
                 }
+                glfw::KeyEvent(key, _, glfw::Repeat, modifiers) => {
+                    self.modifiers = glfw_map_modifiers(modifiers);
+                    self.event_queue.push(
+                        input::Repeat(input::Keyboard(glfw_map_key(key)))
+                    );
+                }
                 glfw::MouseButtonEvent(button, glfw::Press, _) => {
                     self.event_queue.push(
                         input::Press(input::Mouse(glfw_map_mouse(button)))
@@ -150,11 +393,83 @@ impl GameWindowGLFW {
                 glfw::ScrollEvent(x, y) => {
                     self.event_queue.push(input::Move(input::MouseScroll(x, y)));
                 }
+                glfw::FramebufferSizeEvent(w, h) => {
+                    self.settings.size = [w as u32, h as u32];
+                    self.event_queue.push(input::Resize(w as u32, h as u32));
+                }
+                glfw::FocusEvent(focused) => {
+                    self.event_queue.push(input::Focus(focused));
+                }
+                glfw::CloseEvent => {
+                    // GLFW has already set the should-close flag by the
+                    // time this callback runs. Clear it again so the
+                    // game decides whether to honor the request (by
+                    // calling `close()`) or veto it, instead of the
+                    // window closing out from under it regardless.
+                    self.window.set_should_close(false);
+                    self.event_queue.push(input::Close);
+                }
                 _ => {}
             }
         }
     }
 
+    /// Returns the modifier keys (Shift/Ctrl/Alt/Super) held as of the
+    /// most recent keyboard event passed on to the game, for
+    /// editor-style shortcuts. An Escape press that closes the window
+    /// through `exit_on_esc` does not update them.
+    pub fn get_modifiers(&self) -> keyboard::ModifierKey {
+        self.modifiers
+    }
+
+    /// Sets the cursor shown over the window to a standard shape,
+    /// creating and caching the native cursor the first time each
+    /// shape is requested.
+    ///
+    /// The cache is the sole owner of each `glfw::Cursor`: GLFW does
+    /// not take ownership of the cursor passed to `set_cursor`, so we
+    /// only ever lend it a reference and keep it alive here for as
+    /// long as `self` lives.
+    pub fn set_cursor(&mut self, shape: CursorShape) {
+        let index = shape.clone() as uint;
+        if self.cursors[index].is_none() {
+            let standard = match shape {
+                ArrowCursor => glfw::Arrow,
+                IBeamCursor => glfw::IBeam,
+                CrosshairCursor => glfw::Crosshair,
+                HandCursor => glfw::Hand,
+                HResizeCursor => glfw::HResize,
+                VResizeCursor => glfw::VResize,
+            };
+            self.cursors[index] = Some(glfw::Cursor::standard(standard));
+        }
+        self.window.set_cursor(self.cursors[index].as_ref());
+    }
+
+    /// Returns the native platform handle backing this window, for
+    /// handing the surface to an arbitrary graphics API instead of
+    /// the built-in GL path.
+    #[cfg(target_os = "windows")]
+    pub fn get_native_handle(&self) -> NativeHandle {
+        Win32(self.window.get_win32_window())
+    }
+
+    /// Returns the native platform handle backing this window, for
+    /// handing the surface to an arbitrary graphics API instead of
+    /// the built-in GL path.
+    #[cfg(target_os = "linux")]
+    pub fn get_native_handle(&self) -> NativeHandle {
+        X11(self.window.get_x11_display(), self.window.get_x11_window())
+    }
+
+    /// Returns the native platform handle backing this window, for
+    /// handing the surface to an arbitrary graphics API instead of
+    /// the built-in GL path.
+    #[cfg(target_os = "macos")]
+    pub fn get_native_handle(&self) -> NativeHandle {
+        Cocoa(self.window.get_cocoa_window())
+    }
+
     /// Creates a gfx device and frame.
     pub fn gfx(&self) -> (gfx::GlDevice, gfx::Frame) {
         let device = gfx::GlDevice::new(|s|
@@ -351,3 +666,50 @@ fn glfw_map_mouse(mouse_button: glfw::MouseButton) -> mouse::Button {
     }
 }
 
+// Translates GLFW's native modifier bitflags into piston's
+// keyboard::ModifierKey, keeping glfw::Modifiers out of the public API.
+fn glfw_map_modifiers(modifiers: glfw::Modifiers) -> keyboard::ModifierKey {
+    let mut keys = keyboard::NO_MODIFIER;
+    if modifiers.contains(glfw::Shift) { keys = keys | keyboard::SHIFT; }
+    if modifiers.contains(glfw::Control) { keys = keys | keyboard::CTRL; }
+    if modifiers.contains(glfw::Alt) { keys = keys | keyboard::ALT; }
+    if modifiers.contains(glfw::Super) { keys = keys | keyboard::GUI; }
+    keys
+}
+
+// Maps a raw joystick button index onto a standard gamepad layout.
+// Indices follow the common Xbox-style ordering most drivers report.
+fn glfw_map_joystick_button(index: uint) -> GamepadButton {
+    match index {
+        0 => GamepadA,
+        1 => GamepadB,
+        2 => GamepadX,
+        3 => GamepadY,
+        4 => GamepadLeftBumper,
+        5 => GamepadRightBumper,
+        6 => GamepadBack,
+        7 => GamepadStart,
+        8 => GamepadGuide,
+        9 => GamepadLeftThumb,
+        10 => GamepadRightThumb,
+        11 => GamepadDpadUp,
+        12 => GamepadDpadRight,
+        13 => GamepadDpadDown,
+        14 => GamepadDpadLeft,
+        _ => GamepadUnknown(index as u8),
+    }
+}
+
+// Maps a raw joystick axis index onto a standard gamepad layout.
+fn glfw_map_joystick_axis(index: uint) -> GamepadAxis {
+    match index {
+        0 => GamepadLeftStickX,
+        1 => GamepadLeftStickY,
+        2 => GamepadRightStickX,
+        3 => GamepadRightStickY,
+        4 => GamepadLeftTrigger,
+        5 => GamepadRightTrigger,
+        _ => GamepadUnknownAxis(index as u8),
+    }
+}
+